@@ -0,0 +1,49 @@
+use hyper::{header, Body, Response, StatusCode};
+use serde_json::json;
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    BadJson(serde_json::Error),
+    InvalidBlock,
+    ChainLocked,
+    #[allow(dead_code)]
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadJson(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidBlock => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::ChainLocked => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::BadJson(e) => format!("invalid request body: {}", e),
+            ApiError::InvalidBlock => "mined block failed validation".to_owned(),
+            ApiError::ChainLocked => "blockchain lock was poisoned".to_owned(),
+            ApiError::Internal(msg) => msg.clone(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::BadJson(e)
+    }
+}
+
+impl From<ApiError> for Response<Body> {
+    fn from(err: ApiError) -> Self {
+        let body = json!({ "error": err.message() }).to_string();
+
+        Response::builder()
+            .status(err.status())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| Response::new(Body::from("Internal Server Error")))
+    }
+}
@@ -1,8 +1,14 @@
+mod error;
+mod p2p;
+mod rpc;
+mod spec;
+
 use dotenv::dotenv;
 use futures::{future, Future, Stream};
 use hex::encode as hex_encode;
 use hyper::service::service_fn;
 use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::env;
@@ -10,30 +16,42 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::process::exit;
 use std::sync::{Arc, Mutex};
 
-#[derive(Clone, Debug, Default, Serialize)]
-struct Block {
+use error::ApiError;
+use rpc::SubscriberList;
+use spec::ChainSpec;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Block {
     index: u64,
     timestamp: String,
     bpm: u64,
     hash: String,
     prev_hash: String,
+    nonce: u64,
+    difficulty: usize,
 }
 
-#[derive(Debug)]
-struct Blockchain(Vec<Block>);
+pub(crate) struct Blockchain {
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) db: Connection,
+    pub(crate) difficulty: usize,
+}
 
 #[derive(Deserialize)]
 struct Message {
     bpm: u64,
 }
 
-type ResponseFuture = Box<Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
 static NOTFOUND: &[u8] = b"Not Found";
 
-fn calculate_hash(block: &Block) -> String {
-    let record =
-        block.index.to_string() + &block.timestamp + &block.bpm.to_string() + &block.prev_hash;
+pub(crate) fn calculate_hash(block: &Block) -> String {
+    let record = block.index.to_string()
+        + &block.timestamp
+        + &block.bpm.to_string()
+        + &block.prev_hash
+        + &block.nonce.to_string();
 
     let mut hasher = Sha256::new();
     hasher.input(record);
@@ -41,7 +59,11 @@ fn calculate_hash(block: &Block) -> String {
     hex_encode(hasher.result().as_slice())
 }
 
-fn generate_block(old_block: &Block, bpm: u64) -> Block {
+fn hash_meets_difficulty(hash: &str, difficulty: usize) -> bool {
+    hash.chars().take(difficulty).all(|c| c == '0')
+}
+
+pub(crate) fn generate_block(old_block: &Block, bpm: u64, difficulty: usize) -> Block {
     let mut new_block = Block::default();
 
     use chrono::prelude::*;
@@ -51,29 +73,118 @@ fn generate_block(old_block: &Block, bpm: u64) -> Block {
     new_block.timestamp = t.to_string();
     new_block.bpm = bpm;
     new_block.prev_hash = old_block.hash.to_owned();
+    new_block.difficulty = difficulty;
+
+    new_block.nonce = 0;
     new_block.hash = calculate_hash(&new_block);
+    while !hash_meets_difficulty(&new_block.hash, difficulty) {
+        new_block.nonce += 1;
+        new_block.hash = calculate_hash(&new_block);
+    }
 
     new_block
 }
 
-fn init_blockchain() -> Arc<Mutex<Blockchain>> {
-    let mut init_block = Block::default();
+fn ensure_schema(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            idx         INTEGER PRIMARY KEY,
+            timestamp   TEXT NOT NULL,
+            bpm         INTEGER NOT NULL,
+            hash        TEXT NOT NULL,
+            prev_hash   TEXT NOT NULL,
+            nonce       INTEGER NOT NULL,
+            difficulty  INTEGER NOT NULL
+        )",
+        params![],
+    )
+    .expect("failed to create blocks table");
+}
+
+fn open_db() -> Connection {
+    let db_path = env::var("DB_PATH").unwrap_or_else(|_| "blockchain.db".to_owned());
+    let conn = Connection::open(db_path).expect("failed to open blockchain database");
 
-    use chrono::prelude::*;
-    let t = Utc::now();
+    ensure_schema(&conn);
 
-    init_block.index = 0;
-    init_block.timestamp = t.to_string();
-    init_block.bpm = 0;
-    init_block.prev_hash = "".to_string();
-    init_block.hash = calculate_hash(&init_block);
+    conn
+}
 
-    println!("{:?}", init_block);
+fn load_blocks(db: &Connection) -> Vec<Block> {
+    let mut stmt = db
+        .prepare("SELECT idx, timestamp, bpm, hash, prev_hash, nonce, difficulty FROM blocks ORDER BY idx")
+        .expect("failed to prepare block query");
+
+    stmt.query_map(params![], |row| {
+        Ok(Block {
+            index: row.get::<_, i64>(0)? as u64,
+            timestamp: row.get(1)?,
+            bpm: row.get::<_, i64>(2)? as u64,
+            hash: row.get(3)?,
+            prev_hash: row.get(4)?,
+            nonce: row.get::<_, i64>(5)? as u64,
+            difficulty: row.get::<_, i64>(6)? as usize,
+        })
+    })
+    .expect("failed to read blocks")
+    .collect::<rusqlite::Result<Vec<Block>>>()
+    .expect("failed to collect blocks")
+}
 
-    Arc::new(Mutex::new(Blockchain(vec![init_block])))
+pub(crate) fn insert_block(db: &Connection, block: &Block) {
+    db.execute(
+        "INSERT INTO blocks (idx, timestamp, bpm, hash, prev_hash, nonce, difficulty)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            block.index as i64,
+            block.timestamp,
+            block.bpm as i64,
+            block.hash,
+            block.prev_hash,
+            block.nonce as i64,
+            block.difficulty as i64,
+        ],
+    )
+    .expect("failed to persist block");
 }
 
-fn is_block_valid(new_block: &Block, old_block: &Block) -> bool {
+pub(crate) fn replace_blocks(db: &Connection, blocks: &[Block]) {
+    db.execute("DELETE FROM blocks", params![])
+        .expect("failed to clear blocks table");
+
+    for block in blocks {
+        insert_block(db, block);
+    }
+}
+
+fn init_blockchain(spec: &ChainSpec) -> Arc<Mutex<Blockchain>> {
+    let db = open_db();
+    let mut blocks = load_blocks(&db);
+
+    if blocks.is_empty() {
+        let mut init_block = Block::default();
+
+        init_block.index = 0;
+        init_block.timestamp = spec.genesis.timestamp.clone();
+        init_block.bpm = spec.genesis.bpm;
+        init_block.prev_hash = spec.genesis.prev_hash.clone();
+        init_block.difficulty = spec.difficulty;
+        init_block.hash = calculate_hash(&init_block);
+
+        insert_block(&db, &init_block);
+        blocks.push(init_block);
+    }
+
+    println!("{:?}", blocks.last().unwrap());
+
+    Arc::new(Mutex::new(Blockchain {
+        blocks,
+        db,
+        difficulty: spec.difficulty,
+    }))
+}
+
+pub(crate) fn is_block_valid(new_block: &Block, old_block: &Block, difficulty: usize) -> bool {
     if old_block.index + 1 != new_block.index {
         return false;
     }
@@ -86,37 +197,110 @@ fn is_block_valid(new_block: &Block, old_block: &Block) -> bool {
         return false;
     }
 
+    if new_block.difficulty != difficulty {
+        return false;
+    }
+
+    if !hash_meets_difficulty(&new_block.hash, new_block.difficulty) {
+        return false;
+    }
+
     true
 }
 
 fn handle_get_blockchain(block_chain: Arc<Mutex<Blockchain>>) -> ResponseFuture {
-    Box::new(future::ok(respond_with_json(
-        &block_chain.lock().unwrap().0,
-    )))
+    let result = block_chain
+        .lock()
+        .map_err(|_| ApiError::ChainLocked)
+        .map(|block_chain| respond_with_json(&block_chain.blocks));
+
+    Box::new(future::ok(result.unwrap_or_else(Response::from)))
 }
 
 fn handle_post_blockchain(
     req: Request<Body>,
     block_chain: Arc<Mutex<Blockchain>>,
+    peers: Arc<Vec<String>>,
+    subscribers: SubscriberList,
 ) -> ResponseFuture {
     let res = req.into_body().concat2().map(move |chunk| {
-        let msg: Message = serde_json::from_slice(&chunk.into_bytes()).unwrap();
+        let result = mine_and_append_block(&chunk, &block_chain, &peers, &subscribers);
+        result.unwrap_or_else(Response::from)
+    });
 
-        let mut block_chain = block_chain.lock().unwrap();
-        let old_block = &block_chain.0[block_chain.0.len() - 1];
-        let new_block = generate_block(old_block, msg.bpm);
+    Box::new(res)
+}
 
-        if is_block_valid(&new_block, old_block) {
-            println!("{:?}", &new_block);
-            (*block_chain).0.push(new_block.clone());
+fn mine_and_append_block(
+    chunk: &[u8],
+    block_chain: &Arc<Mutex<Blockchain>>,
+    peers: &Arc<Vec<String>>,
+    subscribers: &SubscriberList,
+) -> Result<Response<Body>, ApiError> {
+    let msg: Message = serde_json::from_slice(chunk)?;
+
+    let new_block = {
+        let mut block_chain = block_chain.lock().map_err(|_| ApiError::ChainLocked)?;
+        let difficulty = block_chain.difficulty;
+        let old_block = &block_chain.blocks[block_chain.blocks.len() - 1];
+        let new_block = generate_block(old_block, msg.bpm, difficulty);
+
+        if !is_block_valid(&new_block, old_block, difficulty) {
+            return Err(ApiError::InvalidBlock);
         }
 
-        respond_with_json(&new_block)
+        println!("{:?}", &new_block);
+        insert_block(&block_chain.db, &new_block);
+        block_chain.blocks.push(new_block.clone());
+        new_block
+    };
+
+    p2p::broadcast_block(peers, &new_block);
+    rpc::notify_new_block(subscribers, &new_block);
+
+    Ok(respond_with_json(&new_block))
+}
+
+fn handle_receive_block(
+    req: Request<Body>,
+    block_chain: Arc<Mutex<Blockchain>>,
+    subscribers: SubscriberList,
+) -> ResponseFuture {
+    let res = req.into_body().concat2().map(move |chunk| {
+        let result = append_received_block(&chunk, &block_chain, &subscribers);
+        result.unwrap_or_else(Response::from)
     });
 
     Box::new(res)
 }
 
+fn append_received_block(
+    chunk: &[u8],
+    block_chain: &Arc<Mutex<Blockchain>>,
+    subscribers: &SubscriberList,
+) -> Result<Response<Body>, ApiError> {
+    let block: Block = serde_json::from_slice(chunk)?;
+
+    let accepted = {
+        let mut block_chain = block_chain.lock().map_err(|_| ApiError::ChainLocked)?;
+        let difficulty = block_chain.difficulty;
+        let old_block = &block_chain.blocks[block_chain.blocks.len() - 1];
+
+        if !is_block_valid(&block, old_block, difficulty) {
+            return Err(ApiError::InvalidBlock);
+        }
+
+        println!("{:?}", &block);
+        insert_block(&block_chain.db, &block);
+        block_chain.blocks.push(block.clone());
+        block
+    };
+
+    rpc::notify_new_block(subscribers, &accepted);
+
+    Ok(respond_with_json(&accepted))
+}
+
 fn respond_with_json<T: Serialize>(obj: &T) -> Response<Body> {
     match serde_json::to_string_pretty(obj) {
         Ok(json) => Response::builder()
@@ -133,10 +317,23 @@ fn respond_with_json<T: Serialize>(obj: &T) -> Response<Body> {
     }
 }
 
-fn router(req: Request<Body>, block_chain: &Arc<Mutex<Blockchain>>) -> ResponseFuture {
-    Box::new(match *req.method() {
-        Method::GET => handle_get_blockchain(block_chain.clone()),
-        Method::POST => handle_post_blockchain(req, block_chain.clone()),
+fn router(
+    req: Request<Body>,
+    block_chain: &Arc<Mutex<Blockchain>>,
+    peers: &Arc<Vec<String>>,
+    subscribers: &SubscriberList,
+) -> ResponseFuture {
+    Box::new(match (req.method(), req.uri().path()) {
+        (&Method::GET, "/") => handle_get_blockchain(block_chain.clone()),
+        (&Method::POST, "/") => handle_post_blockchain(
+            req,
+            block_chain.clone(),
+            peers.clone(),
+            subscribers.clone(),
+        ),
+        (&Method::POST, "/blocks") => {
+            handle_receive_block(req, block_chain.clone(), subscribers.clone())
+        }
         _ => {
             let body = Body::from(NOTFOUND);
             Box::new(future::ok(
@@ -149,13 +346,30 @@ fn router(req: Request<Body>, block_chain: &Arc<Mutex<Blockchain>>) -> ResponseF
     })
 }
 
-fn run(addr: SocketAddr) {
-    let block_chain = init_blockchain();
+fn peers_from_env() -> Vec<String> {
+    env::var("PEERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn run(addr: SocketAddr, peers: Vec<String>, spec: ChainSpec, rpc_addr: String) {
+    let block_chain = init_blockchain(&spec);
+    let peers = Arc::new(peers);
+
+    p2p::sync_with_peers(&block_chain, &peers);
+    p2p::spawn_peer_sync(block_chain.clone(), peers.clone());
+
+    let subscribers = rpc::spawn_rpc_server(&rpc_addr, block_chain.clone());
 
     hyper::rt::run(future::lazy(move || {
         let new_service = move || {
             let block_chain = block_chain.clone();
-            service_fn(move |req| router(req, &block_chain))
+            let peers = peers.clone();
+            let subscribers = subscribers.clone();
+            service_fn(move |req| router(req, &block_chain, &peers, &subscribers))
         };
 
         let server = Server::bind(&addr)
@@ -185,5 +399,202 @@ fn main() {
             exit(1);
         });
 
-    run(addr);
+    let rpc_addr = env::var("RPC_ADDR").unwrap_or_else(|_| "127.0.0.1:50001".to_owned());
+
+    run(addr, peers_from_env(), spec::load_chain_spec(), rpc_addr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_block_chain(difficulty: usize) -> Arc<Mutex<Blockchain>> {
+        let db = Connection::open_in_memory().expect("failed to open in-memory db");
+        ensure_schema(&db);
+
+        let genesis = genesis_block(difficulty);
+        insert_block(&db, &genesis);
+
+        Arc::new(Mutex::new(Blockchain {
+            blocks: vec![genesis],
+            db,
+            difficulty,
+        }))
+    }
+
+    fn body_to_string(response: Response<Body>) -> String {
+        let bytes = response
+            .into_body()
+            .concat2()
+            .wait()
+            .expect("failed to read response body");
+        String::from_utf8(bytes.to_vec()).expect("response body was not utf8")
+    }
+
+    fn genesis_block(difficulty: usize) -> Block {
+        let mut genesis = Block {
+            index: 0,
+            timestamp: "genesis".to_owned(),
+            bpm: 0,
+            prev_hash: String::new(),
+            difficulty,
+            ..Block::default()
+        };
+        genesis.hash = calculate_hash(&genesis);
+        genesis
+    }
+
+    #[test]
+    fn hash_meets_difficulty_accepts_enough_leading_zeros() {
+        assert!(hash_meets_difficulty("000abc", 3));
+        assert!(hash_meets_difficulty("abc", 0));
+    }
+
+    #[test]
+    fn hash_meets_difficulty_rejects_too_few_leading_zeros() {
+        assert!(!hash_meets_difficulty("00abc", 3));
+    }
+
+    #[test]
+    fn is_block_valid_accepts_properly_mined_block() {
+        let genesis = genesis_block(2);
+        let next = generate_block(&genesis, 42, 2);
+
+        assert!(is_block_valid(&next, &genesis, 2));
+    }
+
+    #[test]
+    fn is_block_valid_rejects_bad_index() {
+        let genesis = genesis_block(2);
+        let mut next = generate_block(&genesis, 42, 2);
+        next.index = 5;
+
+        assert!(!is_block_valid(&next, &genesis, 2));
+    }
+
+    #[test]
+    fn is_block_valid_rejects_broken_prev_hash_linkage() {
+        let genesis = genesis_block(2);
+        let mut next = generate_block(&genesis, 42, 2);
+        next.prev_hash = "not-the-genesis-hash".to_owned();
+
+        assert!(!is_block_valid(&next, &genesis, 2));
+    }
+
+    #[test]
+    fn is_block_valid_rejects_tampered_payload() {
+        let genesis = genesis_block(2);
+        let mut next = generate_block(&genesis, 42, 2);
+        next.bpm += 1;
+
+        assert!(!is_block_valid(&next, &genesis, 2));
+    }
+
+    #[test]
+    fn is_block_valid_rejects_difficulty_mismatch() {
+        let genesis = genesis_block(2);
+        let next = generate_block(&genesis, 42, 2);
+
+        assert!(!is_block_valid(&next, &genesis, 3));
+    }
+
+    #[test]
+    fn is_block_valid_rejects_hash_not_meeting_its_own_difficulty() {
+        let genesis = genesis_block(1);
+
+        let mut next = Block {
+            index: 1,
+            timestamp: "next".to_owned(),
+            bpm: 7,
+            prev_hash: genesis.hash.clone(),
+            nonce: 0,
+            difficulty: 1,
+            ..Block::default()
+        };
+        next.hash = calculate_hash(&next);
+
+        assert!(!hash_meets_difficulty(&next.hash, 1));
+        assert!(!is_block_valid(&next, &genesis, 1));
+    }
+
+    #[test]
+    fn api_error_bad_json_maps_to_400_with_json_envelope() {
+        let json_err = serde_json::from_str::<serde_json::Value>("not json").unwrap_err();
+        let response: Response<Body> = ApiError::BadJson(json_err).into();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let value: serde_json::Value = serde_json::from_str(&body_to_string(response)).unwrap();
+        assert!(value["error"].as_str().unwrap().starts_with("invalid request body"));
+    }
+
+    #[test]
+    fn api_error_invalid_block_maps_to_422_with_json_envelope() {
+        let response: Response<Body> = ApiError::InvalidBlock.into();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let value: serde_json::Value = serde_json::from_str(&body_to_string(response)).unwrap();
+        assert_eq!(value["error"], "mined block failed validation");
+    }
+
+    #[test]
+    fn api_error_chain_locked_maps_to_500() {
+        let response: Response<Body> = ApiError::ChainLocked.into();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn mine_and_append_block_rejects_bad_json_with_400() {
+        let block_chain = test_block_chain(1);
+        let peers: Arc<Vec<String>> = Arc::new(Vec::new());
+        let subscribers: SubscriberList = Arc::new(Mutex::new(Vec::new()));
+
+        let err = mine_and_append_block(b"not json", &block_chain, &peers, &subscribers).unwrap_err();
+        let response: Response<Body> = err.into();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn append_received_block_accepts_and_appends_a_valid_block() {
+        let block_chain = test_block_chain(1);
+        let subscribers: SubscriberList = Arc::new(Mutex::new(Vec::new()));
+
+        let genesis = block_chain.lock().unwrap().blocks[0].clone();
+        let next = generate_block(&genesis, 10, 1);
+        let body = serde_json::to_vec(&next).unwrap();
+
+        let response = append_received_block(&body, &block_chain, &subscribers).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(block_chain.lock().unwrap().blocks.len(), 2);
+    }
+
+    #[test]
+    fn append_received_block_rejects_an_invalid_block_with_422() {
+        let block_chain = test_block_chain(1);
+        let subscribers: SubscriberList = Arc::new(Mutex::new(Vec::new()));
+
+        let genesis = block_chain.lock().unwrap().blocks[0].clone();
+        let mut tampered = generate_block(&genesis, 10, 1);
+        tampered.bpm += 1;
+        let body = serde_json::to_vec(&tampered).unwrap();
+
+        let err = append_received_block(&body, &block_chain, &subscribers).unwrap_err();
+        let response: Response<Body> = err.into();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(block_chain.lock().unwrap().blocks.len(), 1);
+    }
+
+    #[test]
+    fn append_received_block_rejects_bad_json_with_400() {
+        let block_chain = test_block_chain(1);
+        let subscribers: SubscriberList = Arc::new(Mutex::new(Vec::new()));
+
+        let err = append_received_block(b"not json", &block_chain, &subscribers).unwrap_err();
+        let response: Response<Body> = err.into();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,147 @@
+use crate::{is_block_valid, replace_blocks, Block, Blockchain};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const SYNC_INTERVAL_SECS: u64 = 30;
+
+const PEER_TIMEOUT_SECS: u64 = 5;
+
+fn http_client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(PEER_TIMEOUT_SECS))
+        .build()
+        .expect("failed to build peer http client")
+}
+
+pub(crate) fn is_chain_valid(chain: &[Block], difficulty: usize, local_genesis: &Block) -> bool {
+    match chain.first() {
+        Some(genesis) if genesis.index == 0 && genesis.hash == local_genesis.hash => {}
+        _ => return false,
+    }
+
+    chain
+        .windows(2)
+        .all(|pair| is_block_valid(&pair[1], &pair[0], difficulty))
+}
+
+fn fetch_peer_chain(peer: &str) -> Option<Vec<Block>> {
+    match http_client().get(peer).send() {
+        Ok(resp) => resp.json::<Vec<Block>>().ok(),
+        Err(e) => {
+            eprintln!("failed to fetch chain from peer {}: {}", peer, e);
+            None
+        }
+    }
+}
+
+pub(crate) fn sync_with_peers(block_chain: &Arc<Mutex<Blockchain>>, peers: &[String]) {
+    let (difficulty, genesis) = {
+        let block_chain = block_chain.lock().unwrap();
+        (block_chain.difficulty, block_chain.blocks[0].clone())
+    };
+
+    for peer in peers {
+        let remote_chain = match fetch_peer_chain(peer) {
+            Some(chain) => chain,
+            None => continue,
+        };
+
+        if !is_chain_valid(&remote_chain, difficulty, &genesis) {
+            eprintln!("rejected invalid or foreign chain from peer {}", peer);
+            continue;
+        }
+
+        let mut block_chain = block_chain.lock().unwrap();
+        if remote_chain.len() > block_chain.blocks.len() {
+            println!(
+                "adopting longer chain from peer {} ({} blocks)",
+                peer,
+                remote_chain.len()
+            );
+            replace_blocks(&block_chain.db, &remote_chain);
+            block_chain.blocks = remote_chain;
+        }
+    }
+}
+
+pub(crate) fn broadcast_block(peers: &[String], block: &Block) {
+    for peer in peers {
+        let url = format!("{}/blocks", peer.trim_end_matches('/'));
+        let result = http_client().post(&url).json(block).send();
+
+        if let Err(e) = result {
+            eprintln!("failed to broadcast block to peer {}: {}", url, e);
+        }
+    }
+}
+
+pub(crate) fn spawn_peer_sync(block_chain: Arc<Mutex<Blockchain>>, peers: Arc<Vec<String>>) {
+    if peers.is_empty() {
+        return;
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(SYNC_INTERVAL_SECS));
+        sync_with_peers(&block_chain, &peers);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_block;
+
+    fn genesis_block(difficulty: usize) -> Block {
+        let mut genesis = Block {
+            index: 0,
+            timestamp: "genesis".to_owned(),
+            bpm: 0,
+            prev_hash: String::new(),
+            difficulty,
+            ..Block::default()
+        };
+        genesis.hash = crate::calculate_hash(&genesis);
+        genesis
+    }
+
+    #[test]
+    fn is_chain_valid_accepts_chain_rooted_at_local_genesis() {
+        let genesis = genesis_block(1);
+        let block1 = generate_block(&genesis, 10, 1);
+        let block2 = generate_block(&block1, 20, 1);
+        let chain = vec![genesis.clone(), block1, block2];
+
+        assert!(is_chain_valid(&chain, 1, &genesis));
+    }
+
+    #[test]
+    fn is_chain_valid_rejects_empty_chain() {
+        let genesis = genesis_block(1);
+        assert!(!is_chain_valid(&[], 1, &genesis));
+    }
+
+    #[test]
+    fn is_chain_valid_rejects_foreign_genesis() {
+        let local_genesis = genesis_block(1);
+
+        let mut foreign_genesis = genesis_block(1);
+        foreign_genesis.timestamp = "a different network's genesis".to_owned();
+        foreign_genesis.hash = crate::calculate_hash(&foreign_genesis);
+        let block1 = generate_block(&foreign_genesis, 10, 1);
+        let chain = vec![foreign_genesis, block1];
+
+        assert!(!is_chain_valid(&chain, 1, &local_genesis));
+    }
+
+    #[test]
+    fn is_chain_valid_rejects_broken_linkage() {
+        let genesis = genesis_block(1);
+        let block1 = generate_block(&genesis, 10, 1);
+        let mut block2 = generate_block(&block1, 20, 1);
+        block2.prev_hash = "not-block1s-hash".to_owned();
+        let chain = vec![genesis.clone(), block1, block2];
+
+        assert!(!is_chain_valid(&chain, 1, &genesis));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{Block, Blockchain};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub(crate) type SubscriberList = Arc<Mutex<Vec<TcpStream>>>;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    #[allow(dead_code)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct RpcResponse<'a> {
+    id: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Value>,
+}
+
+pub(crate) fn spawn_rpc_server(addr: &str, block_chain: Arc<Mutex<Blockchain>>) -> SubscriberList {
+    let subscribers: SubscriberList = Arc::new(Mutex::new(Vec::new()));
+    let listener =
+        TcpListener::bind(addr).unwrap_or_else(|e| panic!("failed to bind rpc server on {}: {}", addr, e));
+
+    println!("RPC listening on tcp://{}", addr);
+
+    let accept_subscribers = subscribers.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let block_chain = block_chain.clone();
+                    let subscribers = accept_subscribers.clone();
+                    thread::spawn(move || handle_client(stream, block_chain, subscribers));
+                }
+                Err(e) => eprintln!("rpc accept error: {}", e),
+            }
+        }
+    });
+
+    subscribers
+}
+
+fn handle_client(stream: TcpStream, block_chain: Arc<Mutex<Blockchain>>, subscribers: SubscriberList) {
+    let reader = match stream.try_clone() {
+        Ok(stream) => BufReader::new(stream),
+        Err(e) => {
+            eprintln!("failed to clone rpc stream: {}", e);
+            return;
+        }
+    };
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: RpcRequest = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("bad rpc request: {}", e);
+                continue;
+            }
+        };
+
+        match dispatch(&request, &block_chain, &subscribers, &stream) {
+            Ok(Some(result)) => {
+                let response = RpcResponse {
+                    id: &request.id,
+                    result: Some(result),
+                    error: None,
+                };
+                if write_message(&stream, &response).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => {
+                // dispatch already wrote the response itself, under the subscribers lock.
+            }
+            Err(message) => {
+                let response = RpcResponse {
+                    id: &request.id,
+                    result: None,
+                    error: Some(json!({ "message": message })),
+                };
+                if write_message(&stream, &response).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn dispatch(
+    request: &RpcRequest,
+    block_chain: &Arc<Mutex<Blockchain>>,
+    subscribers: &SubscriberList,
+    stream: &TcpStream,
+) -> Result<Option<Value>, String> {
+    match request.method.as_str() {
+        "blockchain.tip" => {
+            let block_chain = block_chain.lock().unwrap();
+            let tip = block_chain.blocks.last().ok_or("chain is empty")?;
+            Ok(Some(serde_json::to_value(tip).expect("block always serializes")))
+        }
+        "blockchain.block.get" => {
+            let index = request
+                .params
+                .get(0)
+                .and_then(Value::as_u64)
+                .ok_or("missing block index param")?;
+
+            let block_chain = block_chain.lock().unwrap();
+            let block = block_chain
+                .blocks
+                .iter()
+                .find(|b| b.index == index)
+                .ok_or("unknown block index")?;
+            Ok(Some(serde_json::to_value(block).expect("block always serializes")))
+        }
+        "blockchain.headers.subscribe" => {
+            let subscriber_stream = stream.try_clone().map_err(|e| e.to_string())?;
+
+            let tip_value = {
+                let block_chain = block_chain.lock().unwrap();
+                let tip = block_chain.blocks.last().ok_or("chain is empty")?;
+                serde_json::to_value(tip).expect("block always serializes")
+            };
+            let ack = RpcResponse {
+                id: &request.id,
+                result: Some(tip_value),
+                error: None,
+            };
+
+            // Write the ack and register the subscriber under the same lock that
+            // notify_new_block holds while writing, so the two can't interleave
+            // bytes on this socket.
+            let mut subscribers = subscribers.lock().unwrap();
+            write_message(&subscriber_stream, &ack).map_err(|e| e.to_string())?;
+            subscribers.push(subscriber_stream);
+
+            Ok(None)
+        }
+        other => Err(format!("unknown method: {}", other)),
+    }
+}
+
+fn write_message<T: Serialize>(stream: &TcpStream, message: &T) -> std::io::Result<()> {
+    let mut payload = serde_json::to_vec(message).expect("rpc message always serializes");
+    payload.push(b'\n');
+
+    let mut stream = stream;
+    stream.write_all(&payload)
+}
+
+pub(crate) fn notify_new_block(subscribers: &SubscriberList, block: &Block) {
+    let notification = json!({
+        "method": "blockchain.headers.subscribe",
+        "params": [block],
+    });
+
+    let mut subscribers = subscribers.lock().unwrap();
+    subscribers.retain(|stream| write_message(stream, &notification).is_ok());
+}
@@ -0,0 +1,59 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GenesisSpec {
+    pub(crate) timestamp: String,
+    pub(crate) bpm: u64,
+    pub(crate) prev_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChainSpec {
+    #[allow(dead_code)]
+    pub(crate) chain_name: String,
+    #[allow(dead_code)]
+    pub(crate) version: String,
+    pub(crate) difficulty: usize,
+    pub(crate) genesis: GenesisSpec,
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec {
+            chain_name: "dev".to_owned(),
+            version: "1".to_owned(),
+            difficulty: 4,
+            genesis: GenesisSpec {
+                timestamp: "1970-01-01T00:00:00Z".to_owned(),
+                bpm: 0,
+                prev_hash: String::new(),
+            },
+        }
+    }
+}
+
+pub(crate) fn load_chain_spec() -> ChainSpec {
+    match chain_spec_path() {
+        Some(path) => {
+            let contents = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("failed to read chain spec {}: {}", path, e));
+
+            serde_json::from_str(&contents)
+                .unwrap_or_else(|e| panic!("failed to parse chain spec {}: {}", path, e))
+        }
+        None => ChainSpec::default(),
+    }
+}
+
+fn chain_spec_path() -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--chain" {
+            return args.next();
+        }
+    }
+
+    env::var("CHAIN_SPEC").ok()
+}